@@ -3,11 +3,18 @@ package main
 import (
 	"context"
 	"encoding/json"
+	"errors"
+	"flag"
+	"fmt"
 	"html/template"
 	"io"
 	"log"
+	"math/rand"
+	"net"
 	"net/http"
 	"os"
+	"os/signal"
+	"strconv"
 	"strings"
 	"time"
 
@@ -37,33 +44,233 @@ import (
 // - The 'nomic-embed-text' model pulled in Ollama: `ollama pull nomic-embed-text`
 // - The 'gemma:2b' model pulled in Ollama: `ollama pull gemma:2b`
 // - A `wiki.jsonl` file in the same directory with sample data.
+//
+// The defaults below can be overridden by environment variables, which in turn
+// can be overridden by command line flags. Run with `-help` to list them.
 
 const (
 	// We use a local LLM running in Ollama to answer the question.
-	// Ollama provides an OpenAI-compatible API endpoint.
-	ollamaBaseURL = "http://localhost:11434/v1"
+	// Ollama provides an OpenAI-compatible API endpoint. DefraDB talks to the
+	// same server through Ollama's native API instead, see ollamaAPIURL.
+	defaultOllamaBaseURL = "http://localhost:11434/v1"
+
+	// Generating a reply can take a while on consumer hardware, especially on
+	// CPU only. We allow generous time before giving up on a request to Ollama.
+	defaultOllamaTimeout = 2 * time.Minute
+
+	// Connecting, on the other hand, should be quick. If it isn't, Ollama is
+	// most likely not running or not reachable.
+	defaultOllamaConnectTimeout = 10 * time.Second
+
+	// Connections to Ollama are kept open and reused between requests. These
+	// match the defaults of Go's http.DefaultTransport.
+	defaultOllamaKeepAlive    = 30 * time.Second
+	defaultOllamaMaxIdleConns = 2
+	defaultOllamaIdleTimeout  = 90 * time.Second
+
+	// Creating a document makes DefraDB request an embedding from Ollama, and
+	// the first one includes loading the embedding model. A query only runs
+	// locally. We allow up to a minute for either before giving up.
+	defaultDefraTimeout = time.Minute
+
+	// Ollama may still be starting up when we send our first requests. We
+	// retry failed requests a few times, waiting longer after each attempt,
+	// but never longer than the maximum backoff.
+	defaultOllamaMaxAttempts    = 5
+	defaultOllamaInitialBackoff = time.Second
+	defaultOllamaMaxBackoff     = 30 * time.Second
 
 	// We use Google's Gemma (2B), a small but capable model that runs well on
 	// consumer hardware. It's fast and effective for this RAG use case.
 	// Model details: https://huggingface.co/google/gemma-2b
-	llmModel = "gemma:2b"
+	defaultLLMModel = "gemma:2b"
 
 	// The question we want to ask. It's specific enough that a general-purpose
 	// small LLM is unlikely to know the answer.
-	question = "When did the Monarch Company exist?"
+	defaultQuestion = "When did the Monarch Company exist?"
 
 	// We use a local LLM running in Ollama for creating the embeddings.
 	// This model is specifically designed for generating high-quality embeddings.
 	// Model details: https://huggingface.co/nomic-ai/nomic-embed-text-v1.5
-	embeddingModel = "nomic-embed-text"
+	defaultEmbeddingModel = "nomic-embed-text"
+
+	// The knowledge base, one JSON document per line.
+	defaultDataFile = "wiki.jsonl"
 )
 
+// config holds the settings of the example.
+type config struct {
+	ollamaBaseURL        string
+	ollamaTimeout        time.Duration
+	ollamaConnectTimeout time.Duration
+	ollamaKeepAlive      time.Duration
+	ollamaMaxIdleConns   int
+	ollamaIdleTimeout    time.Duration
+	ollamaMaxAttempts    int
+	ollamaInitialBackoff time.Duration
+	ollamaMaxBackoff     time.Duration
+	defraTimeout         time.Duration
+	llmModel             string
+	embeddingModel       string
+	question             string
+	dataFile             string
+}
+
+// loadConfig layers the command line flags over the environment variables
+// over the defaults.
+func loadConfig() config {
+	var cfg config
+	flag.StringVar(&cfg.ollamaBaseURL, "ollama-url", envOr("RAG_OLLAMA_URL", defaultOllamaBaseURL),
+		"base URL of Ollama's OpenAI-compatible API (env RAG_OLLAMA_URL)")
+	flag.DurationVar(&cfg.ollamaTimeout, "ollama-timeout", envDurationOr("RAG_OLLAMA_TIMEOUT", defaultOllamaTimeout),
+		"timeout of a single request to Ollama (env RAG_OLLAMA_TIMEOUT)")
+	flag.DurationVar(&cfg.ollamaConnectTimeout, "ollama-connect-timeout", envDurationOr("RAG_OLLAMA_CONNECT_TIMEOUT", defaultOllamaConnectTimeout),
+		"timeout for connecting to Ollama, 0 for none (env RAG_OLLAMA_CONNECT_TIMEOUT)")
+	flag.DurationVar(&cfg.ollamaKeepAlive, "ollama-keep-alive", envDurationOr("RAG_OLLAMA_KEEP_ALIVE", defaultOllamaKeepAlive),
+		"TCP keep-alive interval of connections to Ollama, negative to disable (env RAG_OLLAMA_KEEP_ALIVE)")
+	flag.IntVar(&cfg.ollamaMaxIdleConns, "ollama-max-idle-conns", envIntOr("RAG_OLLAMA_MAX_IDLE_CONNS", defaultOllamaMaxIdleConns),
+		"idle connections to Ollama kept open for reuse (env RAG_OLLAMA_MAX_IDLE_CONNS)")
+	flag.DurationVar(&cfg.ollamaIdleTimeout, "ollama-idle-timeout", envDurationOr("RAG_OLLAMA_IDLE_TIMEOUT", defaultOllamaIdleTimeout),
+		"how long an idle connection to Ollama is kept open, 0 for no limit (env RAG_OLLAMA_IDLE_TIMEOUT)")
+	flag.IntVar(&cfg.ollamaMaxAttempts, "ollama-max-attempts", envIntOr("RAG_OLLAMA_MAX_ATTEMPTS", defaultOllamaMaxAttempts),
+		"attempts per request to Ollama before giving up (env RAG_OLLAMA_MAX_ATTEMPTS)")
+	flag.DurationVar(&cfg.ollamaInitialBackoff, "ollama-initial-backoff", envDurationOr("RAG_OLLAMA_INITIAL_BACKOFF", defaultOllamaInitialBackoff),
+		"wait before the first retry of a request to Ollama (env RAG_OLLAMA_INITIAL_BACKOFF)")
+	flag.DurationVar(&cfg.ollamaMaxBackoff, "ollama-max-backoff", envDurationOr("RAG_OLLAMA_MAX_BACKOFF", defaultOllamaMaxBackoff),
+		"longest wait between retries of a request to Ollama (env RAG_OLLAMA_MAX_BACKOFF)")
+	flag.DurationVar(&cfg.defraTimeout, "defra-timeout", envDurationOr("RAG_DEFRA_TIMEOUT", defaultDefraTimeout),
+		"timeout of a single request to DefraDB (env RAG_DEFRA_TIMEOUT)")
+	flag.StringVar(&cfg.llmModel, "llm-model", envOr("RAG_LLM_MODEL", defaultLLMModel),
+		"Ollama model used to answer the question (env RAG_LLM_MODEL)")
+	flag.StringVar(&cfg.embeddingModel, "embedding-model", envOr("RAG_EMBEDDING_MODEL", defaultEmbeddingModel),
+		"Ollama model used to create embeddings (env RAG_EMBEDDING_MODEL)")
+	flag.StringVar(&cfg.question, "question", envOr("RAG_QUESTION", defaultQuestion),
+		"question to ask the LLM (env RAG_QUESTION)")
+	flag.StringVar(&cfg.dataFile, "data", envOr("RAG_DATA_FILE", defaultDataFile),
+		"JSON lines file with the knowledge base (env RAG_DATA_FILE)")
+	flag.Parse()
+
+	if cfg.ollamaTimeout <= 0 {
+		log.Fatalf("Invalid timeout for Ollama requests: %s", cfg.ollamaTimeout)
+	}
+	if cfg.ollamaConnectTimeout < 0 {
+		log.Fatalf("Invalid connect timeout for Ollama: %s", cfg.ollamaConnectTimeout)
+	}
+	if cfg.defraTimeout <= 0 {
+		log.Fatalf("Invalid timeout for DefraDB requests: %s", cfg.defraTimeout)
+	}
+	if cfg.ollamaMaxAttempts < 1 {
+		log.Fatalf("Invalid number of attempts for Ollama requests: %d", cfg.ollamaMaxAttempts)
+	}
+	if cfg.ollamaInitialBackoff <= 0 {
+		log.Fatalf("Invalid initial backoff for Ollama requests: %s", cfg.ollamaInitialBackoff)
+	}
+	if cfg.ollamaMaxBackoff < cfg.ollamaInitialBackoff {
+		log.Fatalf("Invalid maximum backoff for Ollama requests: %s is shorter than the initial backoff", cfg.ollamaMaxBackoff)
+	}
+	if cfg.ollamaMaxIdleConns < 1 {
+		log.Fatalf("Invalid number of idle connections to Ollama: %d", cfg.ollamaMaxIdleConns)
+	}
+	if cfg.ollamaIdleTimeout < 0 {
+		log.Fatalf("Invalid idle timeout for connections to Ollama: %s", cfg.ollamaIdleTimeout)
+	}
+	return cfg
+}
+
+// envOr returns the value of the environment variable named by key, or
+// fallback if it is unset or empty.
+func envOr(key, fallback string) string {
+	if v := os.Getenv(key); v != "" {
+		return v
+	}
+	return fallback
+}
+
+// envDurationOr is like envOr for durations such as "30s" or "2m".
+func envDurationOr(key string, fallback time.Duration) time.Duration {
+	v := os.Getenv(key)
+	if v == "" {
+		return fallback
+	}
+	d, err := time.ParseDuration(v)
+	if err != nil {
+		log.Fatalf("Invalid duration in %s: %v", key, err)
+	}
+	return d
+}
+
+// envIntOr is like envOr for integers.
+func envIntOr(key string, fallback int) int {
+	v := os.Getenv(key)
+	if v == "" {
+		return fallback
+	}
+	i, err := strconv.Atoi(v)
+	if err != nil {
+		log.Fatalf("Invalid integer in %s: %v", key, err)
+	}
+	return i
+}
+
 func main() {
-	ctx := context.Background()
+	cfg := loadConfig()
+
+	// The context is cancelled when the user presses Ctrl+C. Every call to
+	// Ollama and DefraDB receives it, so in-flight requests are aborted
+	// instead of running to completion.
+	ctx, stop := signal.NotifyContext(context.Background(), os.Interrupt)
+
+	// Once the context is cancelled, we restore the default behaviour of
+	// Ctrl+C. If shutting down hangs, pressing it again quits immediately.
+	go func() {
+		<-ctx.Done()
+		stop()
+	}()
+
+	// The example itself runs in run, so that its deferred cleanup, like
+	// closing the DefraDB node, is done before we exit here. os.Exit (and
+	// thus log.Fatalf) would skip it.
+	err := run(ctx, cfg)
+	interrupted := ctx.Err() != nil
+	stop()
+	if err != nil {
+		if interrupted {
+			log.Println("Interrupted")
+			// 130 is the conventional exit status for a process ended by
+			// SIGINT (128 + 2).
+			os.Exit(130)
+		}
+		log.Printf("Error: %v", err)
+		os.Exit(1)
+	}
+}
 
-	// // It can take a few seconds for Ollama to load a model into memory for the
-	// // first time. We send a simple request to "warm it up" and ensure it's
-	// // ready before we start the main workflow.
+// run executes the RAG workflow described at the top of this file.
+func run(ctx context.Context, cfg config) error {
+	// A single client is shared by all requests to Ollama: creating the query
+	// embedding as well as asking the LLM.
+	openAIClient := newOllamaClient(cfg)
+
+	// It can take a few seconds for Ollama to load a model into memory for the
+	// first time. We send a simple request to "warm it up": this waits for
+	// Ollama to be up (retrying a few times if it's still starting) and also
+	// loads the LLM into memory, so that the timings of the main workflow
+	// aren't skewed by it. We only ask for a single token, as we don't care
+	// about the reply itself.
+	log.Println("Warming up Ollama...")
+	err := withRetry(ctx, cfg, func(ctx context.Context) error {
+		_, err := openAIClient.CreateChatCompletion(ctx, openai.ChatCompletionRequest{
+			Model: cfg.llmModel,
+			Messages: []openai.ChatCompletionMessage{
+				{Role: openai.ChatMessageRoleUser, Content: "Hello"},
+			},
+			MaxTokens: 1,
+		})
+		return err
+	})
+	if err != nil {
+		return fmt.Errorf("failed to warm up Ollama: %w", err)
+	}
 
 	// --- Step 1: Ask the LLM without RAG ---
 	// We first ask the LLM our question directly to demonstrate that without any
@@ -71,9 +278,12 @@ func main() {
 	log.Println("================================================================================")
 	log.Println("Asking the LLM without providing any external knowledge (no RAG)")
 	log.Println("================================================================================")
-	log.Println("Question: " + question)
+	log.Println("Question: " + cfg.question)
 	log.Println("Asking LLM...")
-	reply := askLLM(ctx, nil, question)
+	reply, err := askLLM(ctx, cfg, openAIClient, nil, cfg.question)
+	if err != nil {
+		return fmt.Errorf("failed to ask the LLM: %w", err)
+	}
 	log.Printf("Initial reply from the LLM: \"%s\"\n\n", reply)
 
 	// --- Step 2: Set up DefraDB and load knowledge base ---
@@ -91,12 +301,14 @@ func main() {
 	db, err := node.New(ctx, node.WithBadgerInMemory(true), node.WithDisableAPI(true), node.WithDisableP2P(true))
 	if err != nil {
 		// For a real application, more robust error handling would be needed.
-		log.Fatalf("Failed to create DefraDB node: %v", err)
+		return fmt.Errorf("failed to create DefraDB node: %w", err)
 	}
-	defer db.Close(ctx)
+	// We close the node with a fresh context, since ctx may already be
+	// cancelled by the time we get here.
+	defer db.Close(context.Background())
 	err = db.Start(ctx)
 	if err != nil {
-		log.Fatalf("Failed to start DefraDB node: %v", err)
+		return fmt.Errorf("failed to start DefraDB node: %w", err)
 	}
 
 	// We define a schema for our data. A schema in DefraDB is similar to a table
@@ -108,29 +320,33 @@ func main() {
 	// - `fields: ["text"]`: Specifies that the embedding should be generated from
 	//   the content of the "text" field.
 	// - `provider: "ollama"`: The embedding provider to use.
-	// - `model: "nomic-embed-text"`: The specific model to use for generating embeddings.
+	// - `model: "nomic-embed-text"`: The specific model to use for generating
+	//   embeddings. We use the configured embedding model here.
+	// - `url: "http://localhost:11434/api"`: The Ollama server DefraDB sends
+	//   the embedding requests to. We use the same server as for our own
+	//   requests, so the query embedding and the document embeddings match.
 	log.Println("Adding 'Wiki' collection schema to DefraDB...")
-	_, err = db.DB.AddSchema(ctx, `type Wiki {
+	_, err = db.DB.AddSchema(ctx, fmt.Sprintf(`type Wiki {
 		text: String
 		category: String
-		text_v: [Float32!] @embedding(fields: ["text"], provider: "ollama", model: "nomic-embed-text")
-	}`)
+		text_v: [Float32!] @embedding(fields: ["text"], provider: "ollama", model: %q, url: %q)
+	}`, cfg.embeddingModel, ollamaAPIURL(cfg.ollamaBaseURL)))
 	if err != nil {
 		// This might fail if the schema is already added. In a real app, you'd
 		// check for this. For this example, we assume a clean start.
-		log.Fatalf("Failed to add schema: %v", err)
+		return fmt.Errorf("failed to add schema: %w", err)
 	}
 
 	// We'll load our knowledge base from a local JSONL file. Each line in the
 	// file represents a document (a small Wiki article in this case).
-	f, err := os.Open("wiki.jsonl")
+	f, err := os.Open(cfg.dataFile)
 	if err != nil {
-		log.Fatalf("Failed to open wiki.jsonl. Make sure the file exists. Error: %v", err)
+		return fmt.Errorf("failed to open %s, make sure the file exists: %w", cfg.dataFile, err)
 	}
 	defer f.Close()
 
 	d := json.NewDecoder(f)
-	log.Println("Reading JSON lines from wiki.jsonl and adding to the 'Wiki' collection...")
+	log.Printf("Reading JSON lines from %s and adding to the 'Wiki' collection...\n", cfg.dataFile)
 	for {
 		var article struct {
 			Text     string `json:"text"`
@@ -140,7 +356,7 @@ func main() {
 		if err == io.EOF {
 			break // Reached end of file
 		} else if err != nil {
-			log.Fatalf("Failed to decode JSON line: %v", err)
+			return fmt.Errorf("failed to decode JSON line: %w", err)
 		}
 
 		// The 'nomic-embed-text' model performs better when a specific prefix is
@@ -158,8 +374,9 @@ func main() {
 		// 3. Store the resulting vector embedding in the `text_v` field.
 		//
 		// Note that we could also generate the embedding manually and assign it to `text_v`.
+		createCtx, cancel := context.WithTimeout(ctx, cfg.defraTimeout)
 		createResult := db.DB.ExecRequest(
-			ctx,
+			createCtx,
 			`mutation CreateWiki($input: [WikiMutationInputArg!]!) {
 				create_Wiki(input: $input) {
 					_docID
@@ -174,12 +391,17 @@ func main() {
 				},
 			}),
 		)
+		cancel()
 		if len(createResult.GQL.Errors) > 0 {
+			// When interrupted, the errors are only about the cancellation.
+			if ctx.Err() != nil {
+				return ctx.Err()
+			}
 			// Log all errors for debugging.
 			for _, gqlErr := range createResult.GQL.Errors {
 				log.Printf("GraphQL error on create: %v\n", gqlErr)
 			}
-			log.Fatalf("Failed to create document in DefraDB.")
+			return errors.New("failed to create document in DefraDB")
 		}
 	}
 	log.Println("Finished loading data into DefraDB.")
@@ -192,23 +414,23 @@ func main() {
 
 	// As mentioned before, the 'nomic-embed-text' model requires a specific
 	// prefix for queries.
-	queryWithPrefix := "search_query: " + question
+	queryWithPrefix := "search_query: " + cfg.question
 
 	// We need to manually create an embedding for our query. We use the same
 	// model and provider that we configured in the DefraDB schema.
 	//
 	// Note that automatically generating the query embedding is on the development roadmap.
 	log.Println("Creating embedding for the query...")
-	openAIClient := openai.NewClientWithConfig(openai.ClientConfig{
-		BaseURL:    ollamaBaseURL,
-		HTTPClient: http.DefaultClient,
-	})
-	embeddingResp, err := openAIClient.CreateEmbeddings(ctx, openai.EmbeddingRequest{
-		Input: []string{queryWithPrefix},
-		Model: embeddingModel,
+	var embeddingResp openai.EmbeddingResponse
+	err = withRetry(ctx, cfg, func(ctx context.Context) (err error) {
+		embeddingResp, err = openAIClient.CreateEmbeddings(ctx, openai.EmbeddingRequest{
+			Input: []string{queryWithPrefix},
+			Model: cfg.embeddingModel,
+		})
+		return err
 	})
 	if err != nil {
-		log.Fatalf("Failed to create query embedding: %v", err)
+		return fmt.Errorf("failed to create query embedding: %w", err)
 	}
 
 	// Now we execute a GraphQL query to find the most relevant documents.
@@ -224,8 +446,10 @@ func main() {
 	//   similarity score below a certain threshold to ensure relevance. This
 	//   threshold may need tuning based on your data and use case.
 	log.Println("Querying DefraDB for similar documents...")
+	queryCtx, cancel := context.WithTimeout(ctx, cfg.defraTimeout)
+	defer cancel()
 	queryResult := db.DB.ExecRequest(
-		ctx,
+		queryCtx,
 		`query Search($queryVector: [Float32!]!) {
 			Wiki(
 				filter: {_alias: {sim: {_gt: 0.63}}},
@@ -241,10 +465,13 @@ func main() {
 		}),
 	)
 	if len(queryResult.GQL.Errors) > 0 {
+		if ctx.Err() != nil {
+			return ctx.Err()
+		}
 		for _, gqlErr := range queryResult.GQL.Errors {
 			log.Printf("GraphQL error on query: %v\n", gqlErr)
 		}
-		log.Fatalf("Failed to query documents from DefraDB.")
+		return errors.New("failed to query documents from DefraDB")
 	}
 
 	log.Printf("Search (incl. query embedding) took %s\n", time.Since(start))
@@ -252,7 +479,7 @@ func main() {
 	resultData, ok := queryResult.GQL.Data.(map[string]any)["Wiki"].([]map[string]any)
 	if !ok || len(resultData) == 0 {
 		log.Println("No relevant documents found in the knowledge base.")
-		return
+		return nil
 	}
 
 	// Print the retrieved documents and their similarity to the question.
@@ -262,7 +489,7 @@ func main() {
 		// Remember to remove the "search_document: " prefix we added earlier
 		// before passing the text to the LLM.
 		content := strings.TrimPrefix(res["text"].(string), "search_document: ")
-		log.Printf(" - Document %d (similarity: %.4f): \"%s...\"\n", i+1, res["sim"], content[:100])
+		log.Printf(" - Document %d (similarity: %.4f): \"%s\"\n", i+1, res["sim"], truncate(content, 100))
 		contexts = append(contexts, content)
 	}
 
@@ -273,7 +500,10 @@ func main() {
 	log.Println("Asking the LLM with retrieved knowledge (with RAG)")
 	log.Println("================================================================================")
 	log.Println("Asking LLM with augmented question...")
-	reply = askLLM(ctx, contexts, question)
+	reply, err = askLLM(ctx, cfg, openAIClient, contexts, cfg.question)
+	if err != nil {
+		return fmt.Errorf("failed to ask the LLM: %w", err)
+	}
 	log.Printf("Reply after augmenting the question with knowledge: \"%s\"\n", reply)
 
 	/* Output (can differ slightly on each run):
@@ -286,6 +516,7 @@ func main() {
 	2024/08/02 14:30:13 Initial reply from the LLM: "I am unable to provide you with the specific dates of the Monarch Company's existence."
 	...
 	*/
+	return nil
 }
 
 // systemPromptTpl is a Go template for generating the system prompt.
@@ -325,30 +556,118 @@ Anything between the following 'context' XML blocks is retrieved from the knowle
 Don't mention the knowledge base, context or search results in your answer.
 `))
 
-// askLLM sends a request to the LLM with an optional context and a question.
-func askLLM(ctx context.Context, contexts []string, question string) string {
+// newOllamaClient creates a client for the configured Ollama server.
+func newOllamaClient(cfg config) *openai.Client {
+	// We start from Go's default transport and apply the configured connection
+	// settings. All requests go to the same host, so the per-host idle limit
+	// is the one that matters.
+	transport := http.DefaultTransport.(*http.Transport).Clone()
+	transport.DialContext = (&net.Dialer{
+		Timeout:   cfg.ollamaConnectTimeout,
+		KeepAlive: cfg.ollamaKeepAlive,
+	}).DialContext
+	transport.MaxIdleConnsPerHost = cfg.ollamaMaxIdleConns
+	transport.IdleConnTimeout = cfg.ollamaIdleTimeout
+
 	// We can use the standard OpenAI client because Ollama exposes an
-	// OpenAI-compatible API. We just need to point the client to the local
+	// OpenAI-compatible API. We just need to point the client to the
 	// Ollama server URL.
-	openAIClient := openai.NewClientWithConfig(openai.ClientConfig{
-		BaseURL:    ollamaBaseURL,
-		HTTPClient: http.DefaultClient,
+	return openai.NewClientWithConfig(openai.ClientConfig{
+		BaseURL:    cfg.ollamaBaseURL,
+		HTTPClient: &http.Client{Transport: transport},
 	})
+}
 
+// ollamaAPIURL returns the URL of Ollama's native API for the base URL of its
+// OpenAI-compatible API. DefraDB uses the native API to create embeddings.
+// Both are served by the same server, under `/api` and `/v1` respectively.
+func ollamaAPIURL(baseURL string) string {
+	return strings.TrimSuffix(strings.TrimSuffix(baseURL, "/"), "/v1") + "/api"
+}
+
+// withRetry calls fn until it succeeds, ctx is cancelled, or
+// cfg.ollamaMaxAttempts attempts have been made. The wait between attempts
+// starts at cfg.ollamaInitialBackoff and doubles each time, plus some random
+// jitter, but never exceeds cfg.ollamaMaxBackoff. Each attempt gets its own
+// context, limited to cfg.ollamaTimeout.
+//
+// Only transient errors are retried, see isTransient. Any other error, such
+// as a model that hasn't been pulled, is returned right away.
+//
+// Only idempotent requests should be retried. Creating an embedding or a
+// chat completion doesn't change any state, so it's safe to send again.
+func withRetry(ctx context.Context, cfg config, fn func(ctx context.Context) error) error {
+	backoff := cfg.ollamaInitialBackoff
+	for attempt := 1; ; attempt++ {
+		attemptCtx, cancel := context.WithTimeout(ctx, cfg.ollamaTimeout)
+		err := fn(attemptCtx)
+		cancel()
+		if err == nil {
+			return nil
+		}
+		if ctx.Err() != nil {
+			return ctx.Err()
+		}
+		if attempt == cfg.ollamaMaxAttempts || !isTransient(err) {
+			return err
+		}
+		// The jitter is up to half the backoff, limited so that the wait stays
+		// within the maximum.
+		jitter := min(backoff/2, cfg.ollamaMaxBackoff-backoff)
+		wait := backoff + time.Duration(rand.Int63n(int64(jitter)+1))
+		log.Printf("Request to Ollama failed (attempt %d of %d), retrying in %s: %v", attempt, cfg.ollamaMaxAttempts, wait.Round(time.Millisecond), err)
+		select {
+		case <-ctx.Done():
+			return ctx.Err()
+		case <-time.After(wait):
+		}
+		if backoff > cfg.ollamaMaxBackoff/2 {
+			backoff = cfg.ollamaMaxBackoff
+		} else {
+			backoff *= 2
+		}
+	}
+}
+
+// isTransient reports whether err is likely to go away when retrying the
+// request: Ollama couldn't be reached, e.g. because it's still starting up,
+// or it responded with a server error or asked us to slow down.
+//
+// A request that ran into its own timeout isn't retried, as it would most
+// likely time out again.
+func isTransient(err error) bool {
+	if errors.Is(err, context.DeadlineExceeded) {
+		return false
+	}
+	var apiErr *openai.APIError
+	if errors.As(err, &apiErr) {
+		return isTransientStatus(apiErr.HTTPStatusCode)
+	}
+	var reqErr *openai.RequestError
+	if errors.As(err, &reqErr) {
+		return isTransientStatus(reqErr.HTTPStatusCode)
+	}
+	var opErr *net.OpError
+	return errors.As(err, &opErr)
+}
+
+// isTransientStatus reports whether an HTTP response with the given status
+// code is worth retrying.
+func isTransientStatus(code int) bool {
+	return code == http.StatusTooManyRequests || code >= http.StatusInternalServerError
+}
+
+// askLLM sends a request to the LLM with an optional context and a question.
+func askLLM(ctx context.Context, cfg config, openAIClient *openai.Client, contexts []string, question string) (string, error) {
 	// We use the template to generate the final system prompt, injecting the
 	// retrieved contexts if they exist.
 	sb := &strings.Builder{}
 	err := systemPromptTpl.Execute(sb, contexts)
 	if err != nil {
 		// This should not happen with a valid template.
-		log.Fatalf("Failed to execute system prompt template: %v", err)
+		return "", fmt.Errorf("failed to execute system prompt template: %w", err)
 	}
 
-	openAIClient.CreateEmbeddings(ctx, openai.EmbeddingRequest{
-		Input: []string{question},
-		Model: embeddingModel,
-	})
-
 	// We construct the chat messages. The conversation consists of:
 	// 1. The system prompt (our instructions to the LLM).
 	// 2. The user's question.
@@ -362,16 +681,31 @@ func askLLM(ctx context.Context, contexts []string, question string) string {
 		},
 	}
 
-	res, err := openAIClient.CreateChatCompletion(ctx, openai.ChatCompletionRequest{
-		Model:    llmModel,
-		Messages: messages,
+	var res openai.ChatCompletionResponse
+	err = withRetry(ctx, cfg, func(ctx context.Context) (err error) {
+		res, err = openAIClient.CreateChatCompletion(ctx, openai.ChatCompletionRequest{
+			Model:    cfg.llmModel,
+			Messages: messages,
+		})
+		return err
 	})
 	if err != nil {
-		log.Fatalf("Ollama chat completion failed: %v", err)
+		return "", err
 	}
 
 	// The response from the LLM might have leading/trailing whitespace,
 	// so we trim it for a cleaner output.
 	reply := res.Choices[0].Message.Content
-	return strings.TrimSpace(reply)
+	return strings.TrimSpace(reply), nil
+}
+
+// truncate shortens s to its first n characters followed by "...", if it's
+// longer than that. It counts runes rather than bytes, so multi-byte
+// characters are never cut in half.
+func truncate(s string, n int) string {
+	runes := []rune(s)
+	if len(runes) <= n {
+		return s
+	}
+	return string(runes[:n]) + "..."
 }